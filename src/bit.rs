@@ -1,54 +1,263 @@
 use std::io;
-use byteorder::LittleEndian;
-use byteorder::ReadBytesExt;
 use byteorder::WriteBytesExt;
 
+/// The bit packing order used by a `BitReader`/`BitWriter`.
+///
+/// `LsbFirst` is DEFLATE's convention: the least significant bit of each
+/// value is emitted first and packed into the low end of the output byte.
+/// `MsbFirst` is the opposite convention used by e.g. JPEG: the most
+/// significant bit is emitted first and packed into the high end of the
+/// output byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    LsbFirst,
+    MsbFirst,
+}
+impl Default for BitOrder {
+    fn default() -> Self {
+        BitOrder::LsbFirst
+    }
+}
+
+/// The widest single read or write. A refill only pulls in whole bytes, so
+/// satisfying a request can leave up to 7 bits more than were asked for in
+/// the u64 cache (and a writer keeps up to 7 bits pending between writes),
+/// so `64 - 7 = 57` is the largest width guaranteed to fit in every state.
+/// DEFLATE's widest single token (a Huffman code
+/// plus its extra bits) tops out at 15 + 13 = 28 bits, well within this.
+const MAX_BITWIDTH: u8 = 57;
+
+#[inline(always)]
+fn reverse_u8(b: u8) -> u8 {
+    let b = (b & 0xF0) >> 4 | (b & 0x0F) << 4;
+    let b = (b & 0xCC) >> 2 | (b & 0x33) << 2;
+    (b & 0xAA) >> 1 | (b & 0x55) << 1
+}
+
+/// Reverses the bit order of a full 64-bit word, using the same
+/// swap-based doubling trick as `reverse_u8`.
+#[inline(always)]
+fn reverse_u64(v: u64) -> u64 {
+    let v = (v & 0x5555555555555555) << 1 | (v >> 1) & 0x5555555555555555;
+    let v = (v & 0x3333333333333333) << 2 | (v >> 2) & 0x3333333333333333;
+    let v = (v & 0x0F0F0F0F0F0F0F0F) << 4 | (v >> 4) & 0x0F0F0F0F0F0F0F0F;
+    let v = (v & 0x00FF00FF00FF00FF) << 8 | (v >> 8) & 0x00FF00FF00FF00FF;
+    let v = (v & 0x0000FFFF0000FFFF) << 16 | (v >> 16) & 0x0000FFFF0000FFFF;
+    (v << 32) | (v >> 32)
+}
+
+/// Reverses the order of the low `width` bits of `value` (the rest must
+/// be zero). Used to turn the per-byte bit reversal that `BitReader`
+/// applies while filling its cache in `MsbFirst` mode back into the
+/// natural (first-bit-read-is-the-result's-MSB) value a caller expects
+/// from a multi-bit read. Runs on every `peek_bits` call in `MsbFirst`
+/// mode, so this uses the same O(1) swap trick as `reverse_u8` instead
+/// of a bit-at-a-time loop: reversing the whole word moves `value`'s low
+/// `width` bits to its high `width` bits (in reversed order), so shifting
+/// back down by `64 - width` lands them where the caller expects.
+#[inline(always)]
+fn reverse_bits(value: u64, width: u8) -> u64 {
+    reverse_u64(value) >> (64 - width)
+}
+
+/// A source of individual bits, abstracting over `BitReader<R>` so DEFLATE
+/// decoding logic can be written against `B: BitRead` instead of a
+/// concrete buffer (e.g. to swap in a zero-copy, slice-backed reader, or
+/// an instrumented wrapper, without touching the decode logic itself).
+pub trait BitRead {
+    fn read_bit(&mut self) -> io::Result<bool>;
+    fn read_bits(&mut self, bitwidth: u8) -> io::Result<u64>;
+    fn peek_bits(&mut self, bitwidth: u8) -> io::Result<u64>;
+    fn skip_bits(&mut self, bitwidth: u8);
+    fn reset(&mut self);
+    fn order(&self) -> BitOrder;
+    /// The total number of bits consumed so far by `read_bit`,
+    /// `read_bits`, `skip_bits`, `align_to_byte` or `read_bytes`.
+    /// `peek_bits` alone never advances this, so peeking stays
+    /// side-effect-free.
+    fn tell_bits(&self) -> u64;
+    /// Discards the sub-byte bits left over in the cache so the next read
+    /// starts at the next byte boundary of the underlying stream. DEFLATE's
+    /// stored blocks start byte-aligned, so this is the usual prelude to
+    /// `read_bytes`. Fails with `UnexpectedEof` if an implementation
+    /// enforcing a bounded extent (e.g. `TakeBits`) doesn't have enough
+    /// budget left to discard the padding.
+    fn align_to_byte(&mut self) -> io::Result<()>;
+    /// Reads `buf.len()` bytes: first any whole bytes still sitting in the
+    /// cache, then the rest straight through from the underlying stream.
+    /// The caller must be byte-aligned first (see `align_to_byte`).
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()>;
+
+    /// `tell_bits`, rounded up to a whole number of bytes.
+    fn tell(&self) -> u64 {
+        (self.tell_bits() + 7) / 8
+    }
+    /// Wraps `self` in a `TakeBits` that refuses to read past `limit_bits`
+    /// more bits, analogous to `std::io::Read::take`.
+    fn take_bits(self, limit_bits: u64) -> TakeBits<Self>
+        where Self: Sized
+    {
+        TakeBits::new(self, limit_bits)
+    }
+}
+
+/// A sink for individual bits, abstracting over `BitWriter<W>` the same
+/// way `BitRead` abstracts over `BitReader<R>`.
+pub trait BitWrite {
+    fn write_bit(&mut self, bit: bool) -> io::Result<()>;
+    fn write_bits(&mut self, bitwidth: u8, bits: u64) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    fn order(&self) -> BitOrder;
+    /// The total number of bits emitted so far via `write_bit`,
+    /// `write_bits` or `write_bytes`.
+    fn tell_bits(&self) -> u64;
+    /// Pads with zero bits up to the next byte boundary, flushing them
+    /// out immediately. DEFLATE's stored blocks start byte-aligned, so
+    /// this is the usual prelude to `write_bytes`.
+    fn align_to_byte(&mut self) -> io::Result<()>;
+    /// Writes `buf` straight through to the underlying stream. The
+    /// caller must be byte-aligned first (see `align_to_byte`).
+    fn write_bytes(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// `tell_bits`, rounded up to a whole number of bytes.
+    fn tell(&self) -> u64 {
+        (self.tell_bits() + 7) / 8
+    }
+}
+
 #[derive(Debug)]
 pub struct BitWriter<W> {
     inner: W,
-    buf: u32,
+    buf: u64,
     end: u8,
+    order: BitOrder,
+    bits_written: u64,
 }
 impl<W> BitWriter<W>
     where W: io::Write
 {
     pub fn new(inner: W) -> Self {
+        Self::with_order(inner, BitOrder::LsbFirst)
+    }
+    pub fn with_order(inner: W, order: BitOrder) -> Self {
         BitWriter {
             inner: inner,
             buf: 0,
             end: 0,
+            order: order,
+            bits_written: 0,
         }
     }
+    // These forward to the `BitWrite` impl below; kept inherent so
+    // existing call sites don't need `BitWrite` in scope.
     #[inline(always)]
     pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
-        self.write_bits(1, bit as u16)
+        BitWrite::write_bit(self, bit)
     }
     #[inline(always)]
-    pub fn write_bits(&mut self, bitwidth: u8, bits: u16) -> io::Result<()> {
-        debug_assert!(bitwidth < 16);
-        debug_assert!(self.end + bitwidth <= 32);
-        self.buf |= (bits as u32) << self.end;
+    pub fn write_bits(&mut self, bitwidth: u8, bits: u64) -> io::Result<()> {
+        BitWrite::write_bits(self, bitwidth, bits)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        BitWrite::flush(self)
+    }
+    pub fn order(&self) -> BitOrder {
+        BitWrite::order(self)
+    }
+    pub fn tell_bits(&self) -> u64 {
+        BitWrite::tell_bits(self)
+    }
+    pub fn tell(&self) -> u64 {
+        BitWrite::tell(self)
+    }
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        BitWrite::align_to_byte(self)
+    }
+    pub fn write_bytes(&mut self, buf: &[u8]) -> io::Result<()> {
+        BitWrite::write_bytes(self, buf)
+    }
+    /// Drains every complete byte currently buffered, leaving at most 7
+    /// bits (never a whole byte) pending. Called after every write so
+    /// `end` always has room for another `MAX_BITWIDTH`-bit write.
+    #[inline(always)]
+    fn flush_if_needed(&mut self) -> io::Result<()> {
+        while self.end >= 8 {
+            match self.order {
+                BitOrder::LsbFirst => {
+                    try!(self.inner.write_u8(self.buf as u8));
+                    self.buf >>= 8;
+                }
+                BitOrder::MsbFirst => {
+                    try!(self.inner.write_u8((self.buf >> 56) as u8));
+                    self.buf <<= 8;
+                }
+            }
+            self.end -= 8;
+        }
+        Ok(())
+    }
+}
+impl<W> BitWrite for BitWriter<W>
+    where W: io::Write
+{
+    #[inline(always)]
+    fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.write_bits(1, bit as u64)
+    }
+    #[inline(always)]
+    fn write_bits(&mut self, bitwidth: u8, bits: u64) -> io::Result<()> {
+        debug_assert!(bitwidth <= MAX_BITWIDTH);
+        debug_assert!(self.end + bitwidth <= 64);
+        match self.order {
+            BitOrder::LsbFirst => {
+                self.buf |= bits << self.end;
+            }
+            BitOrder::MsbFirst => {
+                self.buf |= bits << (64 - self.end - bitwidth);
+            }
+        }
         self.end += bitwidth;
+        self.bits_written += bitwidth as u64;
         self.flush_if_needed()
     }
-    pub fn flush(&mut self) -> io::Result<()> {
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.flush_if_needed());
         while self.end > 0 {
-            try!(self.inner.write_u8(self.buf as u8));
-            self.buf >>= 8;
+            match self.order {
+                BitOrder::LsbFirst => {
+                    try!(self.inner.write_u8(self.buf as u8));
+                    self.buf >>= 8;
+                }
+                BitOrder::MsbFirst => {
+                    try!(self.inner.write_u8((self.buf >> 56) as u8));
+                    self.buf <<= 8;
+                }
+            }
             self.end = self.end.saturating_sub(8);
         }
         try!(self.inner.flush());
         Ok(())
     }
-    #[inline(always)]
-    fn flush_if_needed(&mut self) -> io::Result<()> {
-        if self.end >= 16 {
-            try!(self.inner.write_u16::<LittleEndian>(self.buf as u16));
-            self.end -= 16;
-            self.buf >>= 16;
+    fn order(&self) -> BitOrder {
+        self.order
+    }
+    fn tell_bits(&self) -> u64 {
+        self.bits_written
+    }
+    fn align_to_byte(&mut self) -> io::Result<()> {
+        let pad = (8 - self.end % 8) % 8;
+        if pad > 0 {
+            try!(self.write_bits(pad, 0));
         }
         Ok(())
     }
+    fn write_bytes(&mut self, buf: &[u8]) -> io::Result<()> {
+        try!(self.flush_if_needed());
+        debug_assert_eq!(self.end, 0, "write_bytes called without align_to_byte");
+        try!(self.inner.write_all(buf));
+        self.bits_written += buf.len() as u64 * 8;
+        Ok(())
+    }
 }
 impl<W> BitWriter<W> {
     pub fn as_inner_ref(&self) -> &W {
@@ -65,58 +274,159 @@ impl<W> BitWriter<W> {
 #[derive(Debug)]
 pub struct BitReader<R> {
     inner: R,
-    last_read: u32,
+    cache: u64,
     offset: u8,
+    order: BitOrder,
+    bits_read: u64,
 }
 impl<R> BitReader<R>
     where R: io::Read
 {
     pub fn new(inner: R) -> Self {
+        Self::with_order(inner, BitOrder::LsbFirst)
+    }
+    pub fn with_order(inner: R, order: BitOrder) -> Self {
         BitReader {
             inner: inner,
-            last_read: 0,
-            offset: 32,
+            cache: 0,
+            offset: 64,
+            order: order,
+            bits_read: 0,
         }
     }
+    // These forward to the `BitRead` impl below; kept inherent so
+    // existing call sites don't need `BitRead` in scope.
     #[inline(always)]
     pub fn read_bit(&mut self) -> io::Result<bool> {
+        BitRead::read_bit(self)
+    }
+    #[inline(always)]
+    pub fn read_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
+        BitRead::read_bits(self, bitwidth)
+    }
+    #[inline(always)]
+    pub fn peek_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
+        BitRead::peek_bits(self, bitwidth)
+    }
+    #[inline(always)]
+    pub fn skip_bits(&mut self, bitwidth: u8) {
+        BitRead::skip_bits(self, bitwidth)
+    }
+    pub fn reset(&mut self) {
+        BitRead::reset(self)
+    }
+    pub fn order(&self) -> BitOrder {
+        BitRead::order(self)
+    }
+    pub fn tell_bits(&self) -> u64 {
+        BitRead::tell_bits(self)
+    }
+    pub fn tell(&self) -> u64 {
+        BitRead::tell(self)
+    }
+    pub fn align_to_byte(&mut self) -> io::Result<()> {
+        BitRead::align_to_byte(self)
+    }
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        BitRead::read_bytes(self, buf)
+    }
+    /// Pulls in however many whole bytes are needed to satisfy a
+    /// `bitwidth`-bit request, via a single buffered read instead of one
+    /// `read_u8` call per byte, and OR-s them all into the cache at once.
+    /// Because callers never request more than `MAX_BITWIDTH` bits, at
+    /// most `(MAX_BITWIDTH as usize + 7) / 8` bytes are ever needed here.
+    #[inline(always)]
+    fn fill_bits(&mut self, bitwidth: u8) -> io::Result<()> {
+        let valid = 64 - self.offset;
+        if bitwidth <= valid {
+            return Ok(());
+        }
+        let needed = bitwidth - valid;
+        let n = (needed + 7) / 8;
+
+        let mut buf = [0u8; (MAX_BITWIDTH as usize + 7) / 8];
+        try!(self.inner.read_exact(&mut buf[..n as usize]));
+
+        let shift = 8 * n as u32;
+        self.cache = if shift < 64 { self.cache >> shift } else { 0 };
+        self.offset -= 8 * n;
+        for (i, &byte) in buf[..n as usize].iter().enumerate() {
+            let byte = match self.order {
+                BitOrder::LsbFirst => byte,
+                BitOrder::MsbFirst => reverse_u8(byte),
+            };
+            let place = 56 - 8 * (n as usize - 1 - i) as u8;
+            self.cache |= (byte as u64) << place;
+        }
+        Ok(())
+    }
+}
+impl<R> BitRead for BitReader<R>
+    where R: io::Read
+{
+    #[inline(always)]
+    fn read_bit(&mut self) -> io::Result<bool> {
         self.read_bits(1).map(|b| b != 0)
     }
     #[inline(always)]
-    pub fn read_bits(&mut self, bitwidth: u8) -> io::Result<u16> {
+    fn read_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
         self.peek_bits(bitwidth).map(|bits| {
             self.skip_bits(bitwidth);
             bits
         })
     }
     #[inline(always)]
-    pub fn peek_bits(&mut self, bitwidth: u8) -> io::Result<u16> {
-        debug_assert!(bitwidth <= 16);
-        while (32 - self.offset) < bitwidth {
-            try!(self.fill_next_u8());
-        }
-        let bits = (self.last_read >> self.offset) as u16;
-        Ok(bits & ((1 << bitwidth) - 1))
+    fn peek_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
+        debug_assert!(bitwidth <= MAX_BITWIDTH);
+        try!(self.fill_bits(bitwidth));
+        let bits = (self.cache >> self.offset) & ((1u64 << bitwidth) - 1);
+        Ok(match self.order {
+            BitOrder::LsbFirst => bits,
+            // Each cached byte was bit-reversed on the way in so that the
+            // FIFO byte order (oldest bits at the low end) still holds;
+            // undo that within the requested width so the result's MSB
+            // is the first bit that was actually read off the wire.
+            BitOrder::MsbFirst => reverse_bits(bits, bitwidth),
+        })
     }
     #[inline(always)]
-    pub fn skip_bits(&mut self, bitwidth: u8) {
-        debug_assert!(32 - self.offset >= bitwidth);
+    fn skip_bits(&mut self, bitwidth: u8) {
+        debug_assert!(64 - self.offset >= bitwidth);
         self.offset += bitwidth;
+        self.bits_read += bitwidth as u64;
     }
-    #[inline(always)]
-    fn fill_next_u8(&mut self) -> io::Result<()> {
-        self.offset -= 8;
-        self.last_read >>= 8;
-
-        let next = try!(self.inner.read_u8()) as u32;
-        self.last_read |= next << (32 - 8);
+    fn reset(&mut self) {
+        self.cache = 0;
+        self.offset = 64;
+        self.bits_read = 0;
+    }
+    fn order(&self) -> BitOrder {
+        self.order
+    }
+    fn tell_bits(&self) -> u64 {
+        self.bits_read
+    }
+    fn align_to_byte(&mut self) -> io::Result<()> {
+        let valid = 64 - self.offset;
+        self.skip_bits(valid % 8);
+        Ok(())
+    }
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        debug_assert_eq!((64 - self.offset) % 8, 0,
+                          "read_bytes called without align_to_byte");
+        let mut i = 0;
+        while self.offset < 64 && i < buf.len() {
+            buf[i] = try!(self.read_bits(8)) as u8;
+            i += 1;
+        }
+        if i < buf.len() {
+            try!(self.inner.read_exact(&mut buf[i..]));
+            self.bits_read += (buf.len() - i) as u64 * 8;
+        }
         Ok(())
     }
 }
 impl<R> BitReader<R> {
-    pub fn reset(&mut self) {
-        self.offset = 32;
-    }
     pub fn as_inner_ref(&self) -> &R {
         &self.inner
     }
@@ -128,6 +438,99 @@ impl<R> BitReader<R> {
     }
 }
 
+/// A `BitRead` adapter that limits the number of bits that can be read
+/// from the wrapped reader, analogous to `std::io::Take`. DEFLATE members
+/// and concatenated gzip streams have known or bounded extents, so a
+/// corrupt length field can't make the decoder consume the rest of the
+/// underlying stream: once the budget is exhausted, further reads fail
+/// with `UnexpectedEof` instead of falling through to `inner`.
+#[derive(Debug)]
+pub struct TakeBits<B> {
+    inner: B,
+    limit_bits: u64,
+    original_limit_bits: u64,
+}
+impl<B> TakeBits<B> {
+    pub fn new(inner: B, limit_bits: u64) -> Self {
+        TakeBits {
+            inner: inner,
+            limit_bits: limit_bits,
+            original_limit_bits: limit_bits,
+        }
+    }
+    /// The number of bits still available before this reader starts
+    /// returning `UnexpectedEof`.
+    pub fn remaining_bits(&self) -> u64 {
+        self.limit_bits
+    }
+    /// Recovers the wrapped reader. Since the budget is tracked entirely
+    /// here rather than by consuming `inner`, `inner` is left at exactly
+    /// the bit position this `TakeBits` last read up to, so the next
+    /// member can be decoded from there (optionally under a fresh
+    /// `TakeBits` of its own).
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+impl<B> BitRead for TakeBits<B>
+    where B: BitRead
+{
+    #[inline(always)]
+    fn read_bit(&mut self) -> io::Result<bool> {
+        self.read_bits(1).map(|b| b != 0)
+    }
+    fn read_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
+        let bits = try!(self.peek_bits(bitwidth));
+        self.skip_bits(bitwidth);
+        Ok(bits)
+    }
+    fn peek_bits(&mut self, bitwidth: u8) -> io::Result<u64> {
+        if (bitwidth as u64) > self.limit_bits {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                       "read past the end of the bit budget"));
+        }
+        self.inner.peek_bits(bitwidth)
+    }
+    fn skip_bits(&mut self, bitwidth: u8) {
+        debug_assert!(bitwidth as u64 <= self.limit_bits);
+        self.inner.skip_bits(bitwidth);
+        self.limit_bits -= bitwidth as u64;
+    }
+    /// Resets the inner reader's position/consumption counters, and
+    /// restores the bit budget to what it was when this `TakeBits` was
+    /// constructed (not just whatever it had been drained down to).
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.limit_bits = self.original_limit_bits;
+    }
+    fn order(&self) -> BitOrder {
+        self.inner.order()
+    }
+    fn tell_bits(&self) -> u64 {
+        self.inner.tell_bits()
+    }
+    fn align_to_byte(&mut self) -> io::Result<()> {
+        let pad = (8 - (self.inner.tell_bits() % 8)) % 8;
+        if pad > self.limit_bits {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                       "read past the end of the bit budget"));
+        }
+        try!(self.inner.align_to_byte());
+        self.limit_bits -= pad;
+        Ok(())
+    }
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let needed = buf.len() as u64 * 8;
+        if needed > self.limit_bits {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                       "read past the end of the bit budget"));
+        }
+        try!(self.inner.read_bytes(buf));
+        self.limit_bits -= needed;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::io;
@@ -161,4 +564,241 @@ mod test {
         assert_eq!(reader.read_bits(8).map_err(|e| e.kind()),
                    Err(io::ErrorKind::UnexpectedEof));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn writer_works_msb_first() {
+        // A single byte-aligned write is unaffected by bit order.
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::MsbFirst);
+        writer.write_bits(8, 0b1010_0101).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.into_inner(), [0b1010_0101]);
+    }
+
+    #[test]
+    fn writer_reader_roundtrip_msb_first() {
+        let mut writer = BitWriter::with_order(Vec::new(), BitOrder::MsbFirst);
+        writer.write_bit(true).unwrap();
+        writer.write_bits(3, 0b010).unwrap();
+        writer.write_bits(11, 0b10101011010).unwrap();
+        writer.flush().unwrap();
+
+        let buf = writer.into_inner();
+        let mut reader = BitReader::with_order(io::Cursor::new(buf), BitOrder::MsbFirst);
+        assert_eq!(reader.read_bit().unwrap(), true);
+        assert_eq!(reader.read_bits(3).unwrap(), 0b010);
+        assert_eq!(reader.read_bits(11).unwrap(), 0b10101011010);
+    }
+
+    #[test]
+    fn reader_works_msb_first() {
+        // A single byte-aligned read is unaffected by bit order.
+        let buf = vec![0b0000_0001];
+        let mut reader = BitReader::with_order(io::Cursor::new(buf), BitOrder::MsbFirst);
+        assert_eq!(reader.read_bits(8).unwrap(), 0b0000_0001);
+    }
+
+    #[test]
+    fn reader_works_msb_first_bit_by_bit() {
+        // Bit-by-bit, MSB-first reads should see the byte's bits from its
+        // most significant bit down to its least significant bit.
+        let buf = vec![0b0000_0001];
+        let mut reader = BitReader::with_order(io::Cursor::new(buf), BitOrder::MsbFirst);
+        let mut bits = Vec::new();
+        for _ in 0..8 {
+            bits.push(reader.read_bit().unwrap());
+        }
+        assert_eq!(bits,
+                   [false, false, false, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn writer_reader_roundtrip_wide_bits() {
+        // 57 bits in one call exercises the new wide-field ceiling.
+        let wide = 0b1_0110_1001_1010_0101_1100_0011_1111_0000_1010_1010_0110_0101u64 &
+                   ((1u64 << 57) - 1);
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(57, wide).unwrap();
+        writer.flush().unwrap();
+
+        let buf = writer.into_inner();
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        assert_eq!(reader.read_bits(57).unwrap(), wide);
+    }
+
+    #[test]
+    fn reader_refill_batches_multiple_bytes_mid_stream() {
+        // Drains the cache down to a handful of bits, then reads a field
+        // wide enough to need several fresh bytes in a single refill.
+        let buf = vec![0b1010_0101, 0xAB, 0xCD, 0xEF, 0x12];
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        assert_eq!(reader.read_bits(5).unwrap(), 0b0_0101);
+        assert_eq!(reader.read_bits(32).unwrap(), 0x977e6d5d);
+    }
+
+    #[test]
+    fn writer_align_and_bulk_write() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(3, 0b101).unwrap();
+        writer.align_to_byte().unwrap();
+        writer.write_bytes(&[0xAB, 0xCD]).unwrap();
+        writer.write_bits(1, 1).unwrap();
+        writer.flush().unwrap();
+
+        let buf = writer.into_inner();
+        assert_eq!(buf, [0b0000_0101, 0xAB, 0xCD, 0b0000_0001]);
+    }
+
+    #[test]
+    fn reader_align_and_bulk_read() {
+        let buf = vec![0b0000_0101, 0xAB, 0xCD, 0b0000_0001];
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        reader.align_to_byte().unwrap();
+
+        let mut bulk = [0u8; 2];
+        reader.read_bytes(&mut bulk).unwrap();
+        assert_eq!(bulk, [0xAB, 0xCD]);
+
+        assert_eq!(reader.read_bits(1).unwrap(), 1);
+    }
+
+    #[test]
+    fn reader_align_drains_cached_bytes_first() {
+        // Peeking ahead fills several bytes into the cache before
+        // align_to_byte/read_bytes run, so read_bytes must drain what's
+        // already cached rather than only reading fresh from `inner`.
+        let buf = vec![0b0000_0000, 0xAB, 0xCD, 0xEF];
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        reader.peek_bits(32).unwrap();
+        reader.skip_bits(8);
+        reader.align_to_byte().unwrap();
+
+        let mut bulk = [0u8; 3];
+        reader.read_bytes(&mut bulk).unwrap();
+        assert_eq!(bulk, [0xAB, 0xCD, 0xEF]);
+    }
+
+    #[test]
+    fn writer_tell_bits() {
+        let mut writer = BitWriter::new(Vec::new());
+        assert_eq!(writer.tell_bits(), 0);
+        writer.write_bits(3, 0b101).unwrap();
+        assert_eq!(writer.tell_bits(), 3);
+        assert_eq!(writer.tell(), 1);
+        writer.align_to_byte().unwrap();
+        assert_eq!(writer.tell_bits(), 8);
+        writer.write_bytes(&[0xAB, 0xCD]).unwrap();
+        assert_eq!(writer.tell_bits(), 24);
+        assert_eq!(writer.tell(), 3);
+    }
+
+    #[test]
+    fn reader_tell_bits() {
+        let buf = vec![0b10100101, 0b11010101];
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        assert_eq!(reader.tell_bits(), 0);
+
+        // Peeking must not advance the counter.
+        reader.peek_bits(5).unwrap();
+        assert_eq!(reader.tell_bits(), 0);
+
+        reader.read_bit().unwrap();
+        assert_eq!(reader.tell_bits(), 1);
+        reader.read_bits(3).unwrap();
+        assert_eq!(reader.tell_bits(), 4);
+        assert_eq!(reader.tell(), 1);
+        reader.align_to_byte().unwrap();
+        assert_eq!(reader.tell_bits(), 8);
+        assert_eq!(reader.tell(), 1);
+
+        reader.reset();
+        assert_eq!(reader.tell_bits(), 0);
+    }
+
+    fn read_nibbles<B: BitRead>(reader: &mut B) -> io::Result<(u64, u64)> {
+        let lo = try!(reader.read_bits(4));
+        let hi = try!(reader.read_bits(4));
+        Ok((lo, hi))
+    }
+
+    #[test]
+    fn generic_over_bit_read() {
+        // Code written against `B: BitRead` should work unchanged against
+        // the concrete `BitReader`.
+        let buf = vec![0b1010_0101];
+        let mut reader = BitReader::new(io::Cursor::new(buf));
+        assert_eq!(read_nibbles(&mut reader).unwrap(), (0b0101, 0b1010));
+    }
+
+    #[test]
+    fn take_bits_stops_at_limit() {
+        let buf = vec![0b1010_0101, 0b1111_0000];
+        let reader = BitReader::new(io::Cursor::new(buf));
+        let mut limited = reader.take_bits(5);
+        assert_eq!(limited.remaining_bits(), 5);
+        assert_eq!(limited.read_bits(3).unwrap(), 0b101);
+        assert_eq!(limited.remaining_bits(), 2);
+        assert_eq!(limited.read_bits(2).unwrap(), 0b00);
+        assert_eq!(limited.remaining_bits(), 0);
+        assert_eq!(limited.read_bit().map_err(|e| e.kind()),
+                   Err(io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn take_bits_into_inner_resumes_at_budget_boundary() {
+        let buf = vec![0b1010_0101, 0b1111_0000];
+        let reader = BitReader::new(io::Cursor::new(buf));
+        let mut limited = reader.take_bits(8);
+        assert_eq!(limited.read_bits(8).unwrap(), 0b1010_0101);
+
+        let mut reader = limited.into_inner();
+        assert_eq!(reader.read_bits(8).unwrap(), 0b1111_0000);
+    }
+
+    #[test]
+    fn take_bits_reset_restores_budget() {
+        let buf = vec![0b1010_0101, 0b1111_0000];
+        let reader = BitReader::new(io::Cursor::new(buf));
+        let mut limited = reader.take_bits(5);
+        limited.read_bits(5).unwrap();
+        assert_eq!(limited.remaining_bits(), 0);
+
+        limited.reset();
+        assert_eq!(limited.remaining_bits(), 5);
+        assert_eq!(limited.tell_bits(), 0);
+    }
+
+    #[test]
+    fn take_bits_align_and_bulk_read_track_budget() {
+        let buf = vec![0b0000_0101, 0xAB, 0xCD, 0xEF];
+        let reader = BitReader::new(io::Cursor::new(buf));
+        let mut limited = reader.take_bits(3 + 5 + 16);
+        assert_eq!(limited.read_bits(3).unwrap(), 0b101);
+        limited.align_to_byte().unwrap();
+        assert_eq!(limited.remaining_bits(), 16);
+
+        let mut bulk = [0u8; 2];
+        limited.read_bytes(&mut bulk).unwrap();
+        assert_eq!(bulk, [0xAB, 0xCD]);
+        assert_eq!(limited.remaining_bits(), 0);
+
+        assert_eq!(limited.read_bit().map_err(|e| e.kind()),
+                   Err(io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn take_bits_align_past_budget_is_eof() {
+        let buf = vec![0b1111_1111, 0xAB];
+        let reader = BitReader::new(io::Cursor::new(buf));
+        let mut limited = reader.take_bits(3);
+        assert_eq!(limited.read_bits(1).unwrap(), 1);
+        assert_eq!(limited.remaining_bits(), 2);
+
+        // Aligning needs to discard 7 padding bits, more than the 2 left
+        // in the budget, so it must fail rather than silently underflow
+        // `limit_bits` or consume past the configured extent.
+        assert_eq!(limited.align_to_byte().map_err(|e| e.kind()),
+                   Err(io::ErrorKind::UnexpectedEof));
+        assert_eq!(limited.remaining_bits(), 2);
+    }
+}